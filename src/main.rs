@@ -9,6 +9,7 @@ use hyper_rustls::HttpsConnector;
 use itertools::Itertools;
 use tokio::{self, time::{Duration, Instant}};
 use rand::random;
+use std::cell::Cell;
 use std::io::Write;
 
 const BASE_POLLS_DEFAULT: usize = 20;
@@ -24,6 +25,57 @@ const ONE_MILLION: u128 = 1_000_000;
 
 const SAMPLE_CHUNK_SIZE: usize = 10;
 
+/// How long to wait for a single poll before treating it as a failure.
+const POLL_TIMEOUT: Duration = Duration::from_secs(10);
+
+const RETRY_MIN_BETWEEN_FAILURES: Duration = Duration::from_millis(500);
+const RETRY_MAX_EXPONENT: u32 = 6;
+const RETRY_TRIES_PER_EXPONENT: usize = 2;
+/// Give up on a poll after this many failed attempts, rather than retrying
+/// forever against a pool that's entirely down.
+const RETRY_MAX_ATTEMPTS: usize = 8;
+
+/// Default fraction, in percent, of a bound's size used to estimate its
+/// 1-sigma error when no ground truth is available.
+const STANDARD_DEVIATION_BOUND_PERCENTAGE_DEFAULT: u128 = 30;
+
+/// The first poll on a fresh connection pays for the TLS handshake, so its
+/// RTT is much larger than a warm connection's. When scheduling off of only
+/// a first poll, divide its delta by this factor to estimate the
+/// steady-state delta instead.
+const FIRST_RTT_TIME_FACTOR: u64 = 5;
+
+/// Time server queried when no `--server` is passed.
+const DEFAULT_SERVER: &str = "https://clients1.google.com/generate_204";
+
+/// Number of samples taken in the `Converge` phase before switching to
+/// `Maintain`.
+const CONVERGE_SAMPLES_DEFAULT: usize = 10;
+/// How long to wait between samples while converging.
+const CONVERGE_MILLIS_BETWEEN_SAMPLES_DEFAULT: u64 = 1_000;
+/// How long to wait between samples once the bound has converged and we're
+/// just tracking drift.
+const MAINTAIN_MILLIS_BETWEEN_SAMPLES_DEFAULT: u64 = 300_000;
+/// Max random jitter added on top of `maintain_millis_between_samples`.
+const MAINTAIN_JITTER_MILLIS_DEFAULT: u64 = 30_000;
+
+/// Which stage of sampling a poll was taken in: rapid initial convergence,
+/// or slow steady-state drift tracking.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Converge,
+    Maintain,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::Converge => write!(f, "converge"),
+            Phase::Maintain => write!(f, "maintain"),
+        }
+    }
+}
+
 #[derive(FromArgs)]
 /// A program that polls an HTTP server repeatedly, and produces data on the
 /// estimated error of the samples. The data is intended to be ingested by a
@@ -40,73 +92,178 @@ struct Args {
     /// number of polls taken to produce data
     #[argh(option, default="POLLS_DEFAULT")]
     polls: usize,
+
+    /// percentage of a bound's size used to estimate its standard deviation
+    #[argh(option, default="STANDARD_DEVIATION_BOUND_PERCENTAGE_DEFAULT")]
+    std_dev_bound_percentage: u128,
+
+    /// time server to poll. May be given multiple times to poll a pool of
+    /// servers; defaults to a single built-in server if omitted
+    #[argh(option)]
+    server: Vec<String>,
+
+    /// number of samples taken in the Converge phase before switching to Maintain
+    #[argh(option, default="CONVERGE_SAMPLES_DEFAULT")]
+    converge_samples: usize,
+
+    /// milliseconds to wait between samples during the Converge phase
+    #[argh(option, default="CONVERGE_MILLIS_BETWEEN_SAMPLES_DEFAULT")]
+    converge_millis_between_samples: u64,
+
+    /// milliseconds to wait between samples during the Maintain phase
+    #[argh(option, default="MAINTAIN_MILLIS_BETWEEN_SAMPLES_DEFAULT")]
+    maintain_millis_between_samples: u64,
+
+    /// max random jitter, in milliseconds, added on top of maintain_millis_between_samples
+    #[argh(option, default="MAINTAIN_JITTER_MILLIS_DEFAULT")]
+    maintain_jitter_millis: u64,
 }
 
 #[tokio::main]
 async fn main() {
-    let Args {outfile, base_polls, polls } = argh::from_env::<Args>();
-
-    let https_sampler = HttpsSampler::new();
+    let Args {
+        outfile, base_polls, polls, std_dev_bound_percentage, server,
+        converge_samples, converge_millis_between_samples,
+        maintain_millis_between_samples, maintain_jitter_millis,
+    } = argh::from_env::<Args>();
+
+    let run_start = Instant::now();
+    let https_sampler = HttpsSampler::new(server);
     // initial polls to get a good initial value
-    let (base_bounds, _) = https_sampler.tight_bound(base_polls).await;
+    let (base_bounds, base_polled, mut failed_polls) = match https_sampler.tight_bound(base_polls).await {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("couldn't get an initial bound, giving up: {}", e);
+            std::process::exit(1);
+        }
+    };
     println!("Initial bound size: {:?}", base_bounds.size());
 
-    // Poll samples without combining
-    let mut inter_bounds = vec![];
-    for _ in 0..polls {
-        let bounds = https_sampler.new_bounds().await;
-        inter_bounds.push(bounds);
-        // Poll at random intervals to try and get a variety of results
-        let sleep_millis: f32 = random::<f32>() * 1000 as f32;
-        let sleep_time = MIN_BETWEEN_POLLS + Duration::from_millis(sleep_millis as u64);
+    // Poll samples without combining. The first `converge_samples` polls run
+    // back-to-back to shrink the bound quickly after startup; afterwards we
+    // switch to a much longer, jittered interval to track drift cheaply.
+    let mut inter_samples = vec![];
+    for i in 0..polls {
+        let phase = if i < converge_samples { Phase::Converge } else { Phase::Maintain };
+        match https_sampler.poll().await {
+            Ok(bounds) => inter_samples.push((bounds, phase)),
+            Err(e) => {
+                eprintln!("skipping {} sample, poll gave up: {}", phase, e);
+                failed_polls += 1;
+            }
+        }
+
+        let sleep_time = match phase {
+            Phase::Converge => Duration::from_millis(converge_millis_between_samples),
+            Phase::Maintain => {
+                let jitter_millis: f32 = random::<f32>() * maintain_jitter_millis as f32;
+                Duration::from_millis(maintain_millis_between_samples)
+                    + Duration::from_millis(jitter_millis as u64)
+            }
+        };
         tokio::time::delay_for(sleep_time).await;
     }
     // take another tight sample at the end.
-    let (final_bounds, _) = https_sampler.tight_bound(base_polls).await;
+    let (final_bounds, final_polled, more_failed_polls) = match https_sampler.tight_bound(base_polls).await {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("couldn't get a final bound, giving up: {}", e);
+            std::process::exit(1);
+        }
+    };
+    failed_polls += more_failed_polls;
     println!("Final bound size: {:?}", final_bounds.size());
 
+    // Collect every poll's raw RTT before inter_samples is consumed below, so
+    // we can report latency percentiles for the whole run. Pull from the
+    // per-poll bound lists rather than base_bounds/final_bounds.deltas, since
+    // the latter only contain bounds that survived tight_bound's outlier
+    // drop -- a poll that got dropped there still completed and has a real
+    // RTT worth reporting.
+    let mut rtts: Vec<Duration> = base_polled.iter().flat_map(|b| b.deltas.iter().map(|d| d.rtt)).collect();
+    rtts.extend(inter_samples.iter().flat_map(|(b, _)| b.deltas.iter().map(|d| d.rtt)));
+    rtts.extend(final_polled.iter().flat_map(|b| b.deltas.iter().map(|d| d.rtt)));
+    report_latency_summary(&mut rtts, run_start.elapsed());
+
     // assume initial and final bounds are pretty good and estimate errors using them
     let mut out = vec![];
-    writeln!(&mut out, "polls,size,delta_avg,delta_max,error").unwrap();
+    writeln!(&mut out, "# failed_polls,{}", failed_polls).unwrap();
+    writeln!(&mut out, "phase,polls,size,delta_avg,delta_max,std_dev,error").unwrap();
     let estimator = ErrorEstimator::new(base_bounds.to_pair(), final_bounds.to_pair());
-    
+
     // produce combinations of the bounds previously sampled and evaluate their errors. If we try
     // to produce combinations accross the whole data set we'll end up with millions of
     // combinations, so chunk the values up first instead.
-    for sample_chunk_iter in &inter_bounds.into_iter().chunks(SAMPLE_CHUNK_SIZE) {
-        let sample_chunk = sample_chunk_iter.collect::<Vec<Bounds>>();
+    //
+    // Two polls landing in the same chunk can come from different servers in
+    // the pool (or one can just be a bad response), so their bounds may not
+    // even overlap. Combine with try_combine and skip any combination that
+    // hits a disjoint pair rather than letting one wrong clock panic the run.
+    let mut dropped_combinations = 0usize;
+    for sample_chunk_iter in &inter_samples.into_iter().chunks(SAMPLE_CHUNK_SIZE) {
+        let sample_chunk = sample_chunk_iter.collect::<Vec<(Bounds, Phase)>>();
         for combination_size in 1..sample_chunk.len()+1 {
             for combination in sample_chunk.iter().combinations(combination_size) {
-                let bound = combination.into_iter().fold(Option::<Bounds>::None, |maybe_b1, b2| {
-                    match maybe_b1 {
-                        Some(b1) => Some(b1.combine(b2)),
-                        None => Some(b2.clone()),
-                    }
-                }).unwrap();
+                let mut acc = Option::<(Bounds, Phase)>::None;
+                let mut disjoint = false;
+                for (b2, p2) in combination {
+                    acc = match acc.take() {
+                        Some((b1, p1)) => match b1.try_combine(b2) {
+                            // A combination spanning both phases is reported as Maintain,
+                            // since that's the phase that was active once fully sampled.
+                            Some(combined) => Some((combined, if *p2 == Phase::Maintain { *p2 } else { p1 })),
+                            None => {
+                                disjoint = true;
+                                break;
+                            }
+                        },
+                        None => Some((b2.clone(), *p2)),
+                    };
+                }
+                if disjoint {
+                    dropped_combinations += 1;
+                    continue;
+                }
+                let (bound, phase) = acc.unwrap();
+                let std_dev = bound.standard_deviation(std_dev_bound_percentage);
                 let err = estimator.estimate_error(bound.to_pair());
-                writeln!(&mut out, "{:?},{:?},{:?},{:?},{:?}",
-                    combination_size, bound.size(), bound.avg_delta(), bound.max_delta(), err).unwrap();
+                writeln!(&mut out, "{},{:?},{:?},{:?},{:?},{:?},{:?}",
+                    phase, combination_size, bound.size(), bound.avg_delta(), bound.max_delta(), std_dev, err).unwrap();
             }
         }
     }
+    if dropped_combinations > 0 {
+        eprintln!("dropped {} outlier combination(s) whose polls had disjoint clocks", dropped_combinations);
+    }
 
     match outfile {
         None => std::io::stdout().write_all(&out).unwrap(),
         Some(filename) => {
             let path = std::path::Path::new(&filename);
-            let mut file = std::fs::File::create(&path).unwrap();
+            let mut file = std::fs::File::create(path).unwrap();
             file.write_all(&out).unwrap();
         }
     }
 }
 
+/// A single poll's contribution to a `Bounds`: half its RTT, whether it was
+/// the first poll on a fresh connection (and so has an inflated RTT from
+/// paying for the TLS handshake), and the raw RTT itself for latency
+/// reporting.
+#[derive(Clone, Copy, Debug)]
+struct DeltaSample {
+    value: u64,
+    is_first: bool,
+    rtt: Duration,
+}
+
 #[derive(Clone, Debug)]
 struct Bounds {
     mono: Instant,
     utc_min: Timestamp,
     utc_max: Timestamp,
     /// deltas of polls used to calculate this bound.
-    deltas: Vec<u64>,
+    deltas: Vec<DeltaSample>,
 }
 
 impl Bounds {
@@ -121,7 +278,11 @@ impl Bounds {
         }
     }
 
-    fn combine(&self, other: &Bounds) -> Bounds {
+    /// Combine with another bound, or `None` if the two don't overlap at
+    /// all. A disjoint bound means one of the two was produced by a
+    /// misbehaving clock, so combining it in would otherwise violate the
+    /// `utc_min <= utc_max` invariant.
+    fn try_combine(&self, other: &Bounds) -> Option<Bounds> {
         let (earlier, later) = if self.mono < other.mono {
             (self, other)
         } else {
@@ -129,16 +290,14 @@ impl Bounds {
         };
 
         let projected = earlier.project(later.mono);
+        let utc_min = std::cmp::max(projected.utc_min, later.utc_min);
+        let utc_max = std::cmp::min(projected.utc_max, later.utc_max);
+        if utc_min > utc_max {
+            return None;
+        }
         let mut new_deltas = self.deltas.clone();
         new_deltas.extend_from_slice(other.deltas.as_slice());
-        let new = Bounds {
-            mono: later.mono,
-            utc_min: std::cmp::max(projected.utc_min, later.utc_min),
-            utc_max: std::cmp::min(projected.utc_max, later.utc_max),
-            deltas: new_deltas
-        };
-        assert!(new.utc_min <= new.utc_max);
-        new
+        Some(Bounds { mono: later.mono, utc_min, utc_max, deltas: new_deltas })
     }
 
     fn to_pair(&self) -> Pair {
@@ -149,68 +308,303 @@ impl Bounds {
         self.utc_max - self.utc_min
     }
 
+    /// Average poll delta of this bound, used to schedule the next poll and
+    /// reported as `delta_avg`. Excludes first-poll
+    /// deltas when warm-connection data is available, since the first poll
+    /// on a fresh connection pays for the TLS handshake and isn't
+    /// representative of steady-state RTT.
     fn avg_delta(&self) -> u64 {
-        self.deltas.iter().fold(0, |a,x| a + x) / self.deltas.len() as u64
+        let warm = self.deltas.iter().filter(|d| !d.is_first);
+        let (sum, count) = warm.fold((0u64, 0u64), |(sum, count), d| (sum + d.value, count + 1));
+        sum.checked_div(count).unwrap_or_else(|| {
+            self.deltas.iter().fold(0, |a,x| a + x.value) / self.deltas.len() as u64
+        })
     }
 
     fn max_delta(&self) -> u64 {
-        self.deltas.iter().fold(0, |a,x| std::cmp::max(a,*x))
+        self.deltas.iter().fold(0, |a,x| std::cmp::max(a,x.value))
+    }
+
+    /// Estimate the 1-sigma error of this bound as `bound_percentage`
+    /// percent of its size. This is a cheap stand-in for a real error
+    /// distribution, tunable so it can be calibrated against ground-truth
+    /// error from `ErrorEstimator`.
+    fn standard_deviation(&self, bound_percentage: u128) -> u128 {
+        self.size() * bound_percentage / 100
+    }
+}
+
+#[cfg(test)]
+mod bounds_tests {
+    use super::*;
+
+    fn bound(mono: Instant, utc_min: Timestamp, utc_max: Timestamp) -> Bounds {
+        Bounds { mono, utc_min, utc_max, deltas: vec![] }
+    }
+
+    #[test]
+    fn try_combine_overlapping_bounds_returns_intersection() {
+        let now = Instant::now();
+        let a = bound(now, 100, 200);
+        let b = bound(now, 150, 250);
+        let combined = a.try_combine(&b).expect("overlapping bounds should combine");
+        assert_eq!(combined.utc_min, 150);
+        assert_eq!(combined.utc_max, 200);
+    }
+
+    #[test]
+    fn try_combine_disjoint_bounds_returns_none() {
+        let now = Instant::now();
+        let a = bound(now, 100, 200);
+        let b = bound(now, 300, 400);
+        assert!(a.try_combine(&b).is_none());
+    }
+}
+
+/// Controls how long to wait between failed polls, and how many to tolerate.
+/// Each consecutive failure moves to the next power-of-two multiple of
+/// `min_between_failures`, capped at `max_exponent`, holding for
+/// `tries_per_exponent` attempts at each step. After `max_attempts` failures
+/// in a row, the poll gives up and reports the last error instead of
+/// retrying forever.
+#[derive(Clone, Copy, Debug)]
+struct RetryStrategy {
+    min_between_failures: Duration,
+    max_exponent: u32,
+    tries_per_exponent: usize,
+    max_attempts: usize,
+}
+
+impl RetryStrategy {
+    /// Wait time before the `attempt_index`'th retry (0-based, counted from
+    /// the first failure of the current poll).
+    fn backoff_duration(&self, attempt_index: usize) -> Duration {
+        let exponent = std::cmp::min(
+            (attempt_index / self.tries_per_exponent) as u32,
+            self.max_exponent,
+        );
+        self.min_between_failures * 2u32.pow(exponent)
+    }
+}
+
+impl Default for RetryStrategy {
+    fn default() -> Self {
+        Self {
+            min_between_failures: RETRY_MIN_BETWEEN_FAILURES,
+            max_exponent: RETRY_MAX_EXPONENT,
+            tries_per_exponent: RETRY_TRIES_PER_EXPONENT,
+            max_attempts: RETRY_MAX_ATTEMPTS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod retry_strategy_tests {
+    use super::*;
+
+    #[test]
+    fn backoff_duration_caps_at_max_exponent() {
+        let retry = RetryStrategy {
+            min_between_failures: Duration::from_millis(100),
+            max_exponent: 2,
+            tries_per_exponent: 2,
+            max_attempts: 8,
+        };
+        assert_eq!(retry.backoff_duration(0), Duration::from_millis(100));
+        assert_eq!(retry.backoff_duration(1), Duration::from_millis(100));
+        assert_eq!(retry.backoff_duration(2), Duration::from_millis(200));
+        assert_eq!(retry.backoff_duration(3), Duration::from_millis(200));
+        // exponent reaches max_exponent (2) here; later attempts stay capped at 2.
+        assert_eq!(retry.backoff_duration(4), Duration::from_millis(400));
+        assert_eq!(retry.backoff_duration(100), Duration::from_millis(400));
+    }
+}
+
+/// Reasons a single poll of a time server can fail.
+#[derive(Debug)]
+enum SamplerError {
+    /// The poll didn't complete within `POLL_TIMEOUT`.
+    Timeout,
+    /// The underlying HTTP request failed.
+    Request(hyper::Error),
+    /// The response had no `Date` header to read a timestamp from.
+    MissingDateHeader,
+    /// The `Date` header wasn't a valid string.
+    InvalidDateHeader,
+    /// The `Date` header didn't parse as an RFC 2822 date.
+    DateParse(chrono::ParseError),
+}
+
+impl std::fmt::Display for SamplerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SamplerError::Timeout => write!(f, "poll timed out after {:?}", POLL_TIMEOUT),
+            SamplerError::Request(e) => write!(f, "request failed: {}", e),
+            SamplerError::MissingDateHeader => write!(f, "response had no Date header"),
+            SamplerError::InvalidDateHeader => write!(f, "Date header was not valid ASCII"),
+            SamplerError::DateParse(e) => write!(f, "failed to parse Date header: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SamplerError {}
+
+/// A single time server in the pool, along with enough state to route polls
+/// to it and to compensate for its connection being cold.
+struct Server {
+    uri: Uri,
+    /// Moving average of this server's RTT, used to pick servers via
+    /// power-of-two-choices. Zero until the first poll completes.
+    rtt_estimate: Cell<Duration>,
+    /// Whether the first poll on this server's connection has completed yet.
+    first_poll_done: Cell<bool>,
+}
+
+impl Server {
+    fn new(uri: Uri) -> Self {
+        Self { uri, rtt_estimate: Cell::new(Duration::from_millis(0)), first_poll_done: Cell::new(false) }
+    }
+
+    fn record_rtt(&self, rtt: Duration) {
+        let prev = self.rtt_estimate.get();
+        let updated = if prev.as_nanos() == 0 { rtt } else { (prev + rtt) / 2 };
+        self.rtt_estimate.set(updated);
     }
 }
 
 struct HttpsSampler {
     client: Client<HttpsConnector<HttpConnector>, Body>,
-    uri: Uri,
+    servers: Vec<Server>,
+    retry: RetryStrategy,
 }
 
 impl HttpsSampler {
-    fn new() -> Self {
+    fn new(server_uris: Vec<String>) -> Self {
         let https = HttpsConnector::new();
         let client = Client::builder().build(https);
-        let uri = "https://clients1.google.com/generate_204".parse().unwrap();
-        Self { client, uri }
+        let server_uris = if server_uris.is_empty() {
+            vec![DEFAULT_SERVER.to_string()]
+        } else {
+            server_uris
+        };
+        let servers = server_uris.into_iter()
+            .map(|uri| Server::new(uri.parse().unwrap()))
+            .collect();
+        Self { client, servers, retry: RetryStrategy::default() }
     }
 
-    /// Poll for a new bounds.
-    async fn new_bounds(&self) -> Bounds {
+    /// Pick a server to poll next via power-of-two-choices: sample two
+    /// candidates at random and return whichever has the lower recent RTT
+    /// estimate, so load spreads across the pool while favoring responsive
+    /// servers.
+    fn choose_server(&self) -> &Server {
+        let a = &self.servers[(random::<f32>() * self.servers.len() as f32) as usize];
+        if self.servers.len() == 1 {
+            return a;
+        }
+        let b = &self.servers[(random::<f32>() * self.servers.len() as f32) as usize];
+        if a.rtt_estimate.get() <= b.rtt_estimate.get() { a } else { b }
+    }
+
+    /// Poll for a new bounds, retrying with capped exponential backoff until
+    /// a poll succeeds or `self.retry.max_attempts` is exhausted, in which
+    /// case the last error is returned to the caller. Each retry re-picks a
+    /// server at random, so with a pool a retry can land on a different
+    /// server than the one that failed; a failure does not change a server's
+    /// RTT estimate.
+    async fn poll(&self) -> Result<Bounds, SamplerError> {
+        let mut attempt = 0;
+        loop {
+            let server = self.choose_server();
+            match self.new_bounds(server).await {
+                Ok(bounds) => return Ok(bounds),
+                Err(e) => {
+                    if attempt + 1 >= self.retry.max_attempts {
+                        eprintln!("poll of {} failed ({}), giving up after {} attempts", server.uri, e, attempt + 1);
+                        return Err(e);
+                    }
+                    let wait = self.retry.backoff_duration(attempt);
+                    eprintln!("poll of {} failed ({}), retrying in {:?}", server.uri, e, wait);
+                    attempt += 1;
+                    tokio::time::delay_for(wait).await;
+                }
+            }
+        }
+    }
+
+    /// Make a single poll attempt against `server`, timing out after
+    /// `POLL_TIMEOUT`.
+    async fn new_bounds(&self, server: &Server) -> Result<Bounds, SamplerError> {
         let before = tokio::time::Instant::now();
-        let resp = self.client.get(self.uri.clone()).await.unwrap();
+        let resp = tokio::time::timeout(POLL_TIMEOUT, self.client.get(server.uri.clone()))
+            .await
+            .map_err(|_| SamplerError::Timeout)?
+            .map_err(SamplerError::Request)?;
         let rtt = before.elapsed();
+        server.record_rtt(rtt);
 
         let utc_date = resp.headers()
-            .get(&hyper::header::DATE).unwrap()
-            .to_str().unwrap();
-        let utc_parsed = DateTime::parse_from_rfc2822(utc_date).unwrap();
+            .get(&hyper::header::DATE).ok_or(SamplerError::MissingDateHeader)?
+            .to_str().map_err(|_| SamplerError::InvalidDateHeader)?;
+        let utc_parsed = DateTime::parse_from_rfc2822(utc_date).map_err(SamplerError::DateParse)?;
         let utc_ts = utc_parsed.timestamp() as u128 * NANOS_IN_SEC;
         let delta = (rtt.as_nanos()) / 2;
-        Bounds {
+        let is_first = !server.first_poll_done.replace(true);
+        Ok(Bounds {
             mono: before + Duration::from_nanos(delta as u64),
             utc_min: utc_ts - delta,
             utc_max: utc_ts + NANOS_IN_SEC + delta,
-            deltas: vec![delta as u64]
-        }
+            deltas: vec![DeltaSample { value: delta as u64, is_first, rtt }]
+        })
     }
 
     /// Get a tight bound by polling multiple times.
-    /// Returns (final bound, list of multiple bounds created)
-    async fn tight_bound(&self, num_polls: usize) -> (Bounds, Vec<Bounds>) {
+    /// Returns (final bound, list of every bound polled, including ones
+    /// dropped as outliers and not folded into the final bound, number of
+    /// polls that gave up).
+    /// Fails only if the very first poll can't get a bound at all; later
+    /// polls that give up are skipped and counted in that number.
+    async fn tight_bound(&self, num_polls: usize) -> Result<(Bounds, Vec<Bounds>, usize), SamplerError> {
         let mut inter_bounds = vec![];
-        let bound = self.new_bounds().await;
+        let mut failed_polls = 0;
+        let bound = self.poll().await?;
         let mut acc_bound = bound.clone();
         inter_bounds.push(bound);
 
         for _ in 1..num_polls {
             tokio::time::delay_until(Self::ideal_time(&acc_bound)).await;
-            let bound = self.new_bounds().await;
-            inter_bounds.push(bound.clone());
-            let i = acc_bound.combine(&bound);
-            acc_bound = i;
+            let bound = match self.poll().await {
+                Ok(bound) => bound,
+                Err(e) => {
+                    eprintln!("skipping sample after poll gave up: {}", e);
+                    failed_polls += 1;
+                    continue;
+                }
+            };
+            match acc_bound.try_combine(&bound) {
+                Some(combined) => acc_bound = combined,
+                None => {
+                    // The server's returned interval doesn't even overlap our
+                    // running bound -- treat it as an outlier and drop it
+                    // rather than letting one wrong clock poison the estimate.
+                    // It's still recorded in inter_bounds so its RTT isn't
+                    // lost to the latency report.
+                    eprintln!("dropping outlier poll disjoint from current bound");
+                }
+            }
+            inter_bounds.push(bound);
         }
-        (acc_bound, inter_bounds)
+        Ok((acc_bound, inter_bounds, failed_polls))
     }
 
     fn ideal_time(bounds: &Bounds) -> Instant {
-        let delta_est = bounds.avg_delta();
+        // If all we have is the first poll on a fresh connection, its delta
+        // is inflated by the TLS handshake. Scale it down rather than
+        // scheduling the next poll as if the connection were already warm.
+        let delta_est = match bounds.deltas.as_slice() {
+            [single] if single.is_first => single.value / FIRST_RTT_TIME_FACTOR,
+            _ => bounds.avg_delta(),
+        };
 
         let subs_off = subs((bounds.utc_min + bounds.utc_max) / 2);
         let ideal = bounds.mono + Duration::from_nanos(NANOS_IN_SEC as u64)
@@ -228,6 +622,52 @@ fn subs(timestamp: Timestamp) -> Timestamp {
     timestamp % NANOS_IN_SEC
 }
 
+/// Percentiles reported by `report_latency_summary`, as fractions in [0, 1].
+const RTT_PERCENTILES: [(&str, f64); 5] =
+    [("p50", 0.50), ("p90", 0.90), ("p95", 0.95), ("p99", 0.99), ("p999", 0.999)];
+
+/// Percentile of `sorted`, taken as the element at `p * (len - 1)` rounded to
+/// the nearest index. `sorted` must be sorted ascending and non-empty.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = (((sorted.len() - 1) as f64) * p).round() as usize;
+    sorted[index]
+}
+
+#[cfg(test)]
+mod percentile_tests {
+    use super::*;
+
+    #[test]
+    fn nearest_index_over_ten_values() {
+        let sorted: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&sorted, 0.0), Duration::from_millis(1));
+        assert_eq!(percentile(&sorted, 0.50), Duration::from_millis(6));
+        assert_eq!(percentile(&sorted, 0.90), Duration::from_millis(9));
+        assert_eq!(percentile(&sorted, 1.0), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn single_value_is_returned_for_any_percentile() {
+        let sorted = [Duration::from_millis(42)];
+        assert_eq!(percentile(&sorted, 0.0), Duration::from_millis(42));
+        assert_eq!(percentile(&sorted, 0.999), Duration::from_millis(42));
+    }
+}
+
+/// Print a summary of round-trip latency collected over the run, so tail
+/// latency can be compared against the error distribution in the CSV output.
+fn report_latency_summary(rtts: &mut [Duration], wall_time: Duration) {
+    if rtts.is_empty() {
+        eprintln!("no polls completed, skipping latency summary");
+        return;
+    }
+    rtts.sort();
+    eprintln!("--- latency summary ({} polls, {:?} wall time) ---", rtts.len(), wall_time);
+    for (label, p) in RTT_PERCENTILES.iter() {
+        eprintln!("{}: {:?}", label, percentile(rtts, *p));
+    }
+}
+
 struct Pair {
     mono: Instant,
     utc: Timestamp,